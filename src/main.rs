@@ -1,13 +1,14 @@
 use std::cmp::max;
-use std::collections::VecDeque;
-use std::thread;
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
 
 #[allow(unused_imports)]
 use rand::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use sdl2::controller::{Axis, Button, GameController};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::messagebox::{self, ButtonData, ClickedButton, MessageBoxButtonFlag, MessageBoxFlag};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
@@ -19,7 +20,10 @@ const HEIGHT: usize = 480;
 #[derive(Debug, Default)]
 struct Obstacle {}
 #[derive(Debug, Default)]
-struct Player {}
+struct Player {
+    velocity_y: f32,
+    jump_origin_y: Option<isize>,
+}
 
 #[derive(Debug, Clone, Copy)]
 struct Position {
@@ -32,8 +36,8 @@ impl Position {
         Position { x, y }
     }
 
-    fn unsafe_left(&mut self) {
-        self.x -= 1;
+    fn unsafe_left(&mut self, amount: isize) {
+        self.x -= amount;
     }
 
     fn left(&mut self) {
@@ -47,18 +51,6 @@ impl Position {
             self.x += 1;
         }
     }
-
-    fn down(&mut self) {
-        if self.y != HEIGHT as isize {
-            self.y += 1;
-        }
-    }
-
-    fn up(&mut self) {
-        if self.y != 0 {
-            self.y -= 1;
-        }
-    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -81,21 +73,46 @@ impl Coverage {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 enum Action {
     Left,
     Right,
-    Up,
-    Down,
+    Jump,
 }
 
 const MAX_OBST: usize = 512;
 
+const GRAVITY: f32 = 0.2;
+const TERMINAL_VELOCITY: f32 = 10.0;
+const JUMP_VELOCITY: f32 = -6.5;
+const MAX_JUMP_HEIGHT: isize = 80;
+
+const BASE_OBSTACLE_SPEED: f64 = 1.0;
+const MAX_OBSTACLE_SPEED: f64 = 6.0;
+const SPEED_SCORE_DIVISOR: f64 = 20.0;
+
+const BASE_GAP: f64 = 200.0;
+const MIN_GAP: f64 = 60.0;
+const GAP_SCORE_DIVISOR: f64 = 3.0;
+
+const BASE_MAX_SPAWN: usize = 10;
+const MAX_MAX_SPAWN: usize = 20;
+const SPAWN_SCORE_DIVISOR: f64 = 15.0;
+
+/// Minimum magnitude a controller's X axis must report before it counts as a move,
+/// so a stick that isn't perfectly centered at rest doesn't drift the player.
+const AXIS_DEADZONE: i16 = 8000;
+
 struct World {
     obstacles: VecDeque<(Obstacle, Position, Coverage)>,
     player: (Player, Position, Coverage),
     last_action: Option<Action>,
     timer: usize,
-    rng: rand::rngs::ThreadRng,
+    rng: StdRng,
+    seed: u64,
+    /// Every action fed into `tick`, in order. Combined with `seed` this fully determines
+    /// the run and can be handed to `replay`.
+    recording: Vec<Option<Action>>,
 }
 
 fn obj_to_rect(p: Position, c: Coverage) -> Rect {
@@ -107,14 +124,26 @@ fn obj_to_rect(p: Position, c: Coverage) -> Rect {
     )
 }
 
-fn is_collided((f_pos, f_cov): (Position, Coverage), (s_pos, _): (Position, Coverage)) -> bool {
-    let in_range = |min: isize, delta: usize, x: isize| min <= x && x <= min + (delta as isize);
-    in_range(f_pos.x, f_cov.width, s_pos.x) && in_range(f_pos.y, f_cov.height, s_pos.y)
+fn ground_y(player_cov: Coverage) -> isize {
+    HEIGHT as isize - player_cov.height() as isize
+}
+
+/// Axis-aligned bounding-box overlap test between two `(Position, Coverage)` boxes.
+/// Symmetric in its arguments, regardless of which box is larger.
+fn is_collided((a_pos, a_cov): (Position, Coverage), (b_pos, b_cov): (Position, Coverage)) -> bool {
+    a_pos.x < b_pos.x + b_cov.width() as isize
+        && a_pos.x + a_cov.width() as isize > b_pos.x
+        && a_pos.y < b_pos.y + b_cov.height() as isize
+        && a_pos.y + a_cov.height() as isize > b_pos.y
 }
 
 impl World {
     fn new() -> Self {
-        let mut rng = rand::thread_rng();
+        Self::with_seed(rand::thread_rng().gen())
+    }
+
+    fn with_seed(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let x = rng.gen_range(0, WIDTH / 2);
         let y = rng.gen_range(0, HEIGHT / 2);
         World {
@@ -127,10 +156,52 @@ impl World {
             last_action: None,
             timer: 0,
             rng,
+            seed,
+            recording: Vec::new(),
         }
     }
 
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn recording(&self) -> &[Option<Action>] {
+        &self.recording
+    }
+
     const SPAWN_DELAY: usize = 150;
+    /// Fixed simulation timestep, in seconds. `tick` always advances the world by this
+    /// much regardless of the render frame rate.
+    const STEP: f64 = 1.0 / 60.0;
+
+    /// The current run's score: one point per `SPAWN_DELAY` ticks survived.
+    fn score(&self) -> f64 {
+        self.timer as f64 / Self::SPAWN_DELAY as f64
+    }
+
+    /// Obstacle horizontal speed, in pixels/tick. Grows with score up to a cap.
+    fn obstacle_speed(score: f64) -> isize {
+        (BASE_OBSTACLE_SPEED + score / SPEED_SCORE_DIVISOR)
+            .min(MAX_OBSTACLE_SPEED)
+            .round() as isize
+    }
+
+    /// Vertical gap the player needs to squeeze through. Shrinks with score, never
+    /// below `MIN_GAP`.
+    fn gap(score: f64) -> f64 {
+        (BASE_GAP - score / GAP_SCORE_DIVISOR).max(MIN_GAP)
+    }
+
+    /// Upper bound (exclusive) of an obstacle's random height, derived from `gap` so a
+    /// tighter gap means taller obstacles.
+    fn max_obstacle_height(score: f64) -> usize {
+        (HEIGHT as f64 - Self::gap(score)).max(6.0) as usize
+    }
+
+    /// Upper bound (exclusive) of how many obstacles spawn per wave. Grows with score.
+    fn max_spawn_count(score: f64) -> usize {
+        (BASE_MAX_SPAWN + (score / SPAWN_SCORE_DIVISOR) as usize).min(MAX_MAX_SPAWN)
+    }
 
     fn check_collisions(&self) -> bool {
         let (pos, cov) = (self.player.1, self.player.2);
@@ -152,29 +223,57 @@ impl World {
     /// Returns true, if player still alive
     fn tick(&mut self) -> bool {
         self.timer += 1;
-        match self.last_action.take() {
+
+        let ground_y = ground_y(self.player.2);
+        let on_ground = self.player.1.y >= ground_y;
+
+        let action = self.last_action.take();
+        self.recording.push(action);
+        match action {
             Some(Action::Left) => self.player.1.left(),
             Some(Action::Right) => self.player.1.right(),
-            Some(Action::Up) => self.player.1.up(),
-            Some(Action::Down) => self.player.1.down(),
-            None => {}
+            Some(Action::Jump) if on_ground => {
+                self.player.0.velocity_y = JUMP_VELOCITY;
+                self.player.0.jump_origin_y = Some(self.player.1.y);
+            }
+            Some(Action::Jump) | None => {}
+        }
+
+        // gravity
+        self.player.0.velocity_y = (self.player.0.velocity_y + GRAVITY).min(TERMINAL_VELOCITY);
+        if self.player.0.velocity_y < 0.0 {
+            if let Some(origin) = self.player.0.jump_origin_y {
+                if origin - self.player.1.y >= MAX_JUMP_HEIGHT {
+                    self.player.0.velocity_y = 0.0;
+                }
+            }
+        }
+        self.player.1.y += self.player.0.velocity_y as isize;
+        if self.player.1.y >= ground_y {
+            self.player.1.y = ground_y;
+            self.player.0.velocity_y = 0.0;
+            self.player.0.jump_origin_y = None;
         }
 
         if self.check_collisions() {
             return false;
         }
 
+        let score = self.score();
+
         // moving obstacles
+        let speed = Self::obstacle_speed(score);
         for (_, p, _) in self.obstacles.iter_mut() {
-            p.unsafe_left();
+            p.unsafe_left(speed);
         }
 
         self.cleanup();
 
         // generating newer ones
         if self.timer % Self::SPAWN_DELAY == 0 {
-            let num = self.rng.gen_range(2, 10);
+            let num = self.rng.gen_range(2, Self::max_spawn_count(score));
             eprintln!("spawned {}", num);
+            let max_height = Self::max_obstacle_height(score);
             for _ in 0..num {
                 let var = (
                     Obstacle {},
@@ -182,7 +281,7 @@ impl World {
                         x: WIDTH as isize,
                         y: self.rng.gen_range(0, HEIGHT) as isize,
                     },
-                    Coverage::new(self.rng.gen_range(5, 32), self.rng.gen_range(5, 32)),
+                    Coverage::new(self.rng.gen_range(5, 32), self.rng.gen_range(5, max_height)),
                 );
                 self.obstacles.push_back(var);
             }
@@ -218,9 +317,251 @@ impl World {
     }
 }
 
+/// Re-runs a recorded sequence of actions against a world seeded the same way it was
+/// recorded, headlessly. Since obstacle spawning only draws from `World`'s seeded rng,
+/// the seed plus the action log fully determine the obstacle layout and outcome, so this
+/// reproduces the original run tick-for-tick. Returns the number of ticks run, counting
+/// the tick on which the player was hit, if any.
+pub fn replay(seed: u64, actions: &[Option<Action>]) -> usize {
+    let mut world = World::with_seed(seed);
+    for action in actions {
+        world.last_action = *action;
+        if !world.tick() {
+            break;
+        }
+    }
+    world.timer
+}
+
+/// A screen in the game's state stack. Only the top of the stack is ticked and fed
+/// input; `run` renders every state in the stack bottom-to-top each frame, so a state
+/// pushed on top (e.g. `PausedState`) can overlay whatever is frozen beneath it.
+trait State {
+    fn handle_event(&mut self, _event: &Event) -> Option<StateChange> {
+        None
+    }
+
+    fn update(&mut self) -> Option<StateChange> {
+        None
+    }
+
+    fn render(&self, canvas: &mut WindowCanvas);
+}
+
+enum StateChange {
+    Push(Box<dyn State>),
+    Pop,
+    Replace(Box<dyn State>),
+}
+
+fn apply_state_change(stack: &mut Vec<Box<dyn State>>, change: StateChange) {
+    match change {
+        StateChange::Push(state) => stack.push(state),
+        StateChange::Pop => {
+            stack.pop();
+        }
+        StateChange::Replace(state) => {
+            stack.pop();
+            stack.push(state);
+        }
+    }
+}
+
+struct MenuState;
+
+impl State for MenuState {
+    fn handle_event(&mut self, event: &Event) -> Option<StateChange> {
+        match event {
+            Event::KeyDown {
+                keycode: Some(Keycode::Return),
+                ..
+            }
+            | Event::ControllerButtonDown {
+                button: Button::Start,
+                ..
+            } => Some(StateChange::Push(Box::new(PlayingState::new()))),
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => Some(StateChange::Pop),
+            _ => None,
+        }
+    }
+
+    fn render(&self, canvas: &mut WindowCanvas) {
+        let _ = canvas
+            .window_mut()
+            .set_title("Simple platformer - Enter to play, Esc to quit");
+    }
+}
+
+struct PlayingState {
+    world: World,
+    /// Horizontal direction held on a controller's X axis, re-applied every tick until
+    /// the axis returns to the deadzone (axis events only fire on change, unlike
+    /// keyboard repeat).
+    axis_move: Option<Action>,
+}
+
+impl PlayingState {
+    fn new() -> Self {
+        PlayingState {
+            world: World::new(),
+            axis_move: None,
+        }
+    }
+}
+
+impl State for PlayingState {
+    fn handle_event(&mut self, event: &Event) -> Option<StateChange> {
+        match event {
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            }
+            | Event::ControllerButtonDown {
+                button: Button::Start,
+                ..
+            } => Some(StateChange::Push(Box::new(PausedState))),
+            Event::KeyDown {
+                keycode: Some(Keycode::Up),
+                ..
+            }
+            | Event::ControllerButtonDown {
+                button: Button::A, ..
+            } => {
+                self.world.last_action = Some(Action::Jump);
+                None
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Left),
+                ..
+            } => {
+                self.world.last_action = Some(Action::Left);
+                None
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Right),
+                ..
+            } => {
+                self.world.last_action = Some(Action::Right);
+                None
+            }
+            Event::ControllerAxisMotion {
+                axis: Axis::LeftX,
+                value,
+                ..
+            } => {
+                self.axis_move = if *value > AXIS_DEADZONE {
+                    Some(Action::Right)
+                } else if *value < -AXIS_DEADZONE {
+                    Some(Action::Left)
+                } else {
+                    None
+                };
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn update(&mut self) -> Option<StateChange> {
+        if self.world.last_action.is_none() {
+            self.world.last_action = self.axis_move;
+        }
+        if self.world.tick() {
+            return None;
+        }
+        eprintln!(
+            "seed: {}, recorded {} actions",
+            self.world.seed(),
+            self.world.recording().len()
+        );
+        Some(StateChange::Replace(Box::new(ResultsState {
+            score: self.world.score(),
+        })))
+    }
+
+    fn render(&self, canvas: &mut WindowCanvas) {
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        if let Err(e) = self.world.draw_obstacles(|x| canvas.draw_rect(x)) {
+            eprintln!("{:?}", e);
+        }
+        canvas.set_draw_color(Color::RGB(0, 255, 255));
+        if let Err(e) = self.world.draw_player(|x| canvas.draw_rect(x)) {
+            eprintln!("{:?}", e);
+        }
+    }
+}
+
+struct PausedState;
+
+impl State for PausedState {
+    fn handle_event(&mut self, event: &Event) -> Option<StateChange> {
+        match event {
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            }
+            | Event::ControllerButtonDown {
+                button: Button::Start,
+                ..
+            } => Some(StateChange::Pop),
+            _ => None,
+        }
+    }
+
+    fn render(&self, canvas: &mut WindowCanvas) {
+        let _ = canvas
+            .window_mut()
+            .set_title("Simple platformer - paused, Esc to resume");
+        canvas.set_draw_color(Color::RGB(255, 255, 0));
+        let _ = canvas.draw_rect(Rect::new(
+            WIDTH as i32 / 2 - 20,
+            HEIGHT as i32 / 2 - 20,
+            40,
+            40,
+        ));
+    }
+}
+
+struct ResultsState {
+    score: f64,
+}
+
+impl State for ResultsState {
+    fn handle_event(&mut self, event: &Event) -> Option<StateChange> {
+        match event {
+            Event::KeyDown {
+                keycode: Some(Keycode::Return),
+                ..
+            }
+            | Event::ControllerButtonDown {
+                button: Button::A, ..
+            } => Some(StateChange::Replace(Box::new(PlayingState::new()))),
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            }
+            | Event::ControllerButtonDown {
+                button: Button::B, ..
+            } => Some(StateChange::Pop),
+            _ => None,
+        }
+    }
+
+    fn render(&self, canvas: &mut WindowCanvas) {
+        let _ = canvas.window_mut().set_title(&format!(
+            "Game over! Score: {:.0} - Enter to retry, Esc for menu",
+            self.score
+        ));
+        canvas.set_draw_color(Color::RGB(128, 0, 0));
+        let _ = canvas.fill_rect(Rect::new(0, 0, WIDTH as u32, HEIGHT as u32));
+    }
+}
+
 pub enum Finished {
     Exit,
-    Restart,
     Error,
 }
 
@@ -234,84 +575,63 @@ pub fn run(canvas: &mut WindowCanvas, sdl_context: &mut Sdl) -> Finished {
     canvas.present();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut world = World::new();
+    let game_controller_subsystem = sdl_context.game_controller().unwrap();
+    let mut controllers: HashMap<u32, GameController> = HashMap::new();
+    for id in 0..game_controller_subsystem.num_joysticks().unwrap_or(0) {
+        if game_controller_subsystem.is_game_controller(id) {
+            if let Ok(controller) = game_controller_subsystem.open(id) {
+                controllers.insert(controller.instance_id(), controller);
+            }
+        }
+    }
+
+    let mut stack: Vec<Box<dyn State>> = vec![Box::new(MenuState)];
+    let mut last_instant = Instant::now();
+    let mut accumulator = 0.0f64;
     loop {
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
+        let now = Instant::now();
+        accumulator += (now - last_instant).as_secs_f64();
+        last_instant = now;
+
         for event in event_pump.poll_iter() {
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => return Finished::Exit,
-                Event::KeyDown {
-                    keycode: Some(Keycode::Down),
-                    ..
-                } => world.last_action = Some(Action::Down),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Up),
-                    ..
-                } => world.last_action = Some(Action::Up),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Left),
-                    ..
-                } => world.last_action = Some(Action::Left),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Right),
-                    ..
-                } => world.last_action = Some(Action::Right),
+                Event::Quit { .. } => return Finished::Exit,
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(controller) = game_controller_subsystem.open(which) {
+                        controllers.insert(controller.instance_id(), controller);
+                    }
+                    continue;
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    controllers.remove(&which);
+                    continue;
+                }
                 _ => {}
             }
+            if let Some(change) = stack.last_mut().and_then(|top| top.handle_event(&event)) {
+                apply_state_change(&mut stack, change);
+            }
         }
-        if !world.tick() {
-            let restart_id = 1;
-            let exit_id = 2;
-            let buttons = [
-                ButtonData {
-                    flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT,
-                    button_id: restart_id,
-                    text: "Restart",
-                },
-                ButtonData {
-                    flags: MessageBoxButtonFlag::ESCAPEKEY_DEFAULT,
-                    button_id: exit_id,
-                    text: "Exit",
-                },
-            ];
-            let points = world.timer as f64 / World::SPAWN_DELAY as f64;
-            let clicked = messagebox::show_message_box(
-                MessageBoxFlag::INFORMATION,
-                &buttons,
-                "Game over!",
-                &format!("Your points: {}", points),
-                canvas.window(),
-                None,
-            );
-            return match clicked {
-                Ok(ClickedButton::CloseButton) => Finished::Exit,
-                Ok(ClickedButton::CustomButton(ButtonData { button_id, .. })) => match button_id {
-                    id if id == &exit_id => Finished::Exit,
-                    id if id == &restart_id => Finished::Restart,
-                    _ => Finished::Error,
-                },
-                Err(_) => Finished::Error,
-            };
+        if stack.is_empty() {
+            return Finished::Exit;
         }
 
-        canvas.set_draw_color(Color::RGB(255, 0, 0));
-        if let Err(e) = world.draw_obstacles(|x| canvas.draw_rect(x)) {
-            eprintln!("{:?}", e);
-            return Finished::Error;
+        while accumulator >= World::STEP {
+            if let Some(change) = stack.last_mut().and_then(|top| top.update()) {
+                apply_state_change(&mut stack, change);
+            }
+            accumulator -= World::STEP;
+            if stack.is_empty() {
+                return Finished::Exit;
+            }
         }
-        canvas.present();
-        canvas.set_draw_color(Color::RGB(0, 255, 255));
-        if let Err(e) = world.draw_player(|x| canvas.draw_rect(x)) {
-            eprintln!("{:?}", e);
-            return Finished::Error;
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        for state in stack.iter() {
+            state.render(canvas);
         }
         canvas.present();
-        thread::sleep(Duration::from_millis(10));
     }
 }
 
@@ -327,5 +647,7 @@ pub fn main() {
 
     let mut canvas = window.into_canvas().build().unwrap();
 
-    while let Finished::Restart = run(&mut canvas, &mut sdl_context) {}
+    if let Finished::Error = run(&mut canvas, &mut sdl_context) {
+        eprintln!("run exited with an error");
+    }
 }